@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{Blockchain, BlockchainError};
+
+// Binary save files start with this magic number and a little-endian
+// format version, followed by the bincode-encoded blockchain. The layout
+// follows the structs field by field, so it changes whenever they do; the
+// version is what keeps a file from being misread, and files from another
+// version are refused rather than converted. Keep JSON for anything that
+// must outlive an upgrade. Version 2 added transaction fees, version 3
+// stores pending transactions as transactions rather than JSON strings,
+// version 4 added proof of stake, version 5 records each block's hash
+// version, version 6 added the supply cap, version 7 reward halving,
+// version 8 transaction kinds, version 9 the difficulty mode, version 10
+// checkpoints, version 11 block metadata, version 12 the UTXO model, and
+// version 13 coinbase maturity.
+const MAGIC: &[u8; 4] = b"RBLK";
+const BINARY_FORMAT_VERSION: u32 = 13;
+const HEADER_LEN: usize = 8;
+
+// Size in bytes of one blockchain saved in each format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodedSize {
+    pub binary: u64,
+    pub json: u64,
+}
+
+impl Blockchain {
+    // Save blockchain to a compact binary file. Much smaller than
+    // `save_to_file`, but not meant to be read by people.
+    pub fn save_to_file_binary(&self, path: &str) -> Result<(), BlockchainError> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&BINARY_FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)?;
+        
+        fs::write(path, bytes)?;
+        
+        Ok(())
+    }
+
+    // `save_to_file_binary`, reporting how the file's size compares with
+    // the JSON that `save_to_file` would write
+    pub fn save_to_file_bincode(&self, path: &str) -> Result<EncodedSize, BlockchainError> {
+        self.save_to_file_binary(path)?;
+        
+        Ok(EncodedSize {
+            binary: fs::metadata(path)?.len(),
+            json: serde_json::to_vec(self)?.len() as u64,
+        })
+    }
+    
+    // Another name for `load_from_file_binary`
+    pub fn load_from_file_bincode(path: &str) -> Result<Blockchain, BlockchainError> {
+        Blockchain::load_from_file_binary(path)
+    }
+    
+    // Load a blockchain saved with `save_to_file_binary`
+    pub fn load_from_file_binary(path: &str) -> Result<Blockchain, BlockchainError> {
+        if !Path::new(path).exists() {
+            return Err(BlockchainError::FileNotFound(path.to_string()));
+        }
+        
+        let bytes = fs::read(path)?;
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(BlockchainError::InvalidFormat(format!("{} is not a binary blockchain file", path)));
+        }
+        
+        let version = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        if version != BINARY_FORMAT_VERSION {
+            return Err(BlockchainError::UnsupportedVersion(version.into()));
+        }
+        
+        let mut blockchain: Blockchain = bincode::deserialize(&bytes[HEADER_LEN..])?;
+        blockchain.rebuild_indexes();
+        
+        Ok(blockchain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, TransactionSigner};
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_binary_round_trip() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let mut blockchain = Blockchain::new(2, 100.0);
+        blockchain.add_block("Text Block".to_string()).unwrap();
+        blockchain.mine_pending_transactions(&alice.address()).unwrap();
+        
+        let mut tx = Transaction::with_nonce(alice.address(), "Bob".to_string(), 10.0, 1);
+        tx.sign(&alice);
+        blockchain.create_transaction(tx).unwrap();
+        blockchain.mine_pending_transactions("Miner1").unwrap();
+        let mut pending = Transaction::new(alice.address(), "Charlie".to_string(), 5.0);
+        pending.sign(&alice);
+        blockchain.create_transaction(pending).unwrap();
+        
+        let (json_path, binary_path) = ("test_binary.json", "test_binary.bin");
+        blockchain.save_to_file(json_path).unwrap();
+        blockchain.save_to_file_binary(binary_path).unwrap();
+        let from_json = Blockchain::load_from_file(json_path).unwrap();
+        let from_binary = Blockchain::load_from_file_binary(binary_path).unwrap();
+        let json_size = fs::metadata(json_path).unwrap().len();
+        let binary_size = fs::metadata(binary_path).unwrap().len();
+        let _ = fs::remove_file(json_path);
+        let _ = fs::remove_file(binary_path);
+        
+        assert!(binary_size < json_size);
+        assert!(from_binary.is_chain_valid());
+        assert_eq!(from_binary.chain.len(), from_json.chain.len());
+        assert_eq!(from_binary.chain[3].data, blockchain.chain[3].data);
+        assert_eq!(from_binary.pending_transactions, blockchain.pending_transactions);
+        assert_eq!(from_binary.balance("Bob"), 10.0);
+    }
+
+    #[test]
+    fn test_bincode_sizes() {
+        let mut blockchain = Blockchain::new(1, 100.0);
+        for _ in 0..5 {
+            blockchain.mine_pending_transactions("Miner1").unwrap();
+        }
+        
+        let path = "test_bincode_sizes.bin";
+        let size = blockchain.save_to_file_bincode(path).unwrap();
+        let loaded = Blockchain::load_from_file_bincode(path).unwrap();
+        let _ = fs::remove_file(path);
+        
+        assert!(size.binary < size.json);
+        assert_eq!(size.json, serde_json::to_string(&blockchain).unwrap().len() as u64);
+        assert!(loaded.is_chain_valid());
+        assert_eq!(serde_json::to_string(&loaded).unwrap(), serde_json::to_string(&blockchain).unwrap());
+    }
+
+    #[test]
+    fn test_binary_header_is_checked() {
+        let path = "test_binary_header.bin";
+        
+        fs::write(path, b"{\"chain\": []}").unwrap();
+        assert!(matches!(Blockchain::load_from_file_binary(path), Err(BlockchainError::InvalidFormat(_))));
+        
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&99u32.to_le_bytes());
+        fs::write(path, bytes).unwrap();
+        assert!(matches!(Blockchain::load_from_file_binary(path), Err(BlockchainError::UnsupportedVersion(99))));
+        
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,82 @@
+use thiserror::Error;
+
+// Everything that can go wrong when working with a blockchain
+#[derive(Debug, Error)]
+pub enum BlockchainError {
+    #[error("Invalid block")]
+    InvalidBlock,
+    
+    #[error("Chain is empty")]
+    EmptyChain,
+    
+    #[error("Invalid transaction: {0}")]
+    InvalidTransaction(String),
+    
+    #[error("Insufficient funds for {sender}: {available} available, {requested} requested")]
+    InsufficientFunds {
+        sender: String,
+        available: f64,
+        requested: f64,
+    },
+    
+    #[error("Transaction {0} is already pending or confirmed")]
+    DuplicateTransaction(String),
+    
+    #[error("Bad nonce: expected {expected}, got {got}")]
+    BadNonce {
+        expected: u64,
+        got: u64,
+    },
+    
+    #[error("Invalid mining reward: {0}")]
+    InvalidReward(String),
+    
+    #[error("No validator has any stake")]
+    NoValidator,
+    
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    
+    #[error("File {0} does not exist")]
+    FileNotFound(String),
+    
+    #[error("Unsupported file format version {0}")]
+    UnsupportedVersion(u64),
+    
+    #[error("Incompatible file format {found}, expected {expected}")]
+    IncompatibleFormat {
+        found: u64,
+        expected: u64,
+    },
+    
+    #[error("Checksum mismatch: {0} is corrupted")]
+    ChecksumMismatch(String),
+    
+    #[error("Corrupt chain: block {first_bad_index} is invalid")]
+    CorruptChain {
+        first_bad_index: u32,
+    },
+    
+    #[error("Cannot roll back {requested} blocks: only {available} lie above the first")]
+    RollbackTooFar {
+        requested: usize,
+        available: usize,
+    },
+    
+    #[error("Invalid file format: {0}")]
+    InvalidFormat(String),
+    
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    
+    #[cfg(feature = "binary")]
+    #[error("Binary encoding error: {0}")]
+    Bincode(#[from] bincode::Error),
+    
+    #[cfg(feature = "sqlite")]
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+}
@@ -0,0 +1,90 @@
+use std::io::Write;
+
+use crate::{Blockchain, BlockchainError};
+
+impl Blockchain {
+    // Write every confirmed transaction as CSV, one row each in chain
+    // order, under a header row. Blocks without transactions (the genesis
+    // block, text blocks, a snapshot) have no rows. The timestamp is the
+    // transaction's own, and the fee is 0 where none was offered.
+    pub fn export_transactions_csv(&self, mut writer: impl Write) -> Result<(), BlockchainError> {
+        writeln!(writer, "block_index,timestamp,sender,recipient,amount,fee")?;
+        
+        for block in &self.chain {
+            for transaction in block.data.transactions() {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{}",
+                    block.index,
+                    transaction.timestamp,
+                    csv_field(&transaction.sender),
+                    csv_field(&transaction.recipient),
+                    transaction.amount,
+                    transaction.fee
+                )?;
+            }
+        }
+        
+        Ok(())
+    }
+
+    // Write one CSV row per block, genesis included, under a header row
+    pub fn export_blocks_csv(&self, mut writer: impl Write) -> Result<(), BlockchainError> {
+        writeln!(writer, "index,timestamp,hash,previous_hash,merkle_root,nonce,difficulty,transactions")?;
+        
+        for block in &self.chain {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                block.index,
+                block.timestamp,
+                block.hash,
+                csv_field(&block.previous_hash),
+                block.merkle_root,
+                block.nonce,
+                block.difficulty,
+                block.data.transactions().len()
+            )?;
+        }
+        
+        Ok(())
+    }
+}
+
+// Quote a field if it holds anything CSV treats specially
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_csv() {
+        let mut blockchain = Blockchain::new(2, 100.0);
+        blockchain.mine_pending_transactions("Miner1").unwrap();
+        blockchain.add_block("Text, with a comma".to_string()).unwrap();
+        blockchain.mine_pending_transactions("Miner, \"Two\"").unwrap();
+        
+        let mut transactions = Vec::new();
+        blockchain.export_transactions_csv(&mut transactions).unwrap();
+        let transactions = String::from_utf8(transactions).unwrap();
+        let rows: Vec<&str> = transactions.lines().collect();
+        assert_eq!(rows[0], "block_index,timestamp,sender,recipient,amount,fee");
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("1,") && rows[1].ends_with(",System,Miner1,100,0"));
+        assert!(rows[2].ends_with(",System,\"Miner, \"\"Two\"\"\",100,0"));
+        
+        let mut blocks = Vec::new();
+        blockchain.export_blocks_csv(&mut blocks).unwrap();
+        let blocks = String::from_utf8(blocks).unwrap();
+        assert_eq!(blocks.lines().count(), 5);
+        assert!(blocks.lines().nth(1).unwrap().starts_with(&format!("0,0,{},0,", blockchain.chain[0].hash)));
+        assert!(blocks.lines().nth(3).unwrap().ends_with(",0"));
+    }
+}
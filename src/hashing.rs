@@ -0,0 +1,66 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+use crate::sha256_hex;
+
+// The hash function used for block hashes (and so for proof of work).
+// Every block records its algorithm, so a chain can be validated without
+// knowing up front how it was mined.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    // Blocks saved before the algorithm was recorded are all SHA-256
+    #[default]
+    Sha256,
+    // SHA-256 applied twice, as in Bitcoin
+    DoubleSha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    // Hex-encoded digest of `data`. All three algorithms produce 32 bytes,
+    // so difficulty means the same thing whichever one is picked.
+    pub fn hash_hex(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Sha256 => sha256_hex(data),
+            HashAlgorithm::DoubleSha256 => {
+                let first = Sha256::digest(data);
+                format!("{:x}", Sha256::digest(first))
+            }
+            HashAlgorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(
+            HashAlgorithm::Sha256.hash_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            HashAlgorithm::DoubleSha256.hash_hex(b"abc"),
+            "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"
+        );
+        assert_eq!(
+            HashAlgorithm::Blake3.hash_hex(b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn test_default_is_sha256() {
+        assert_eq!(HashAlgorithm::default(), HashAlgorithm::Sha256);
+    }
+
+    #[test]
+    fn test_serde_names() {
+        assert_eq!(serde_json::to_string(&HashAlgorithm::DoubleSha256).unwrap(), "\"double_sha256\"");
+        let parsed: HashAlgorithm = serde_json::from_str("\"blake3\"").unwrap();
+        assert_eq!(parsed, HashAlgorithm::Blake3);
+    }
+}
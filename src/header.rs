@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{Block, BlockPayload, Blockchain, DifficultyMode, HashAlgorithm};
+
+// Everything about a block except its payload, which the merkle root
+// stands in for. Enough to check a block's hash and proof of work, and
+// how it links to its parent, without downloading any transactions.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BlockHeader {
+    pub index: u32,
+    pub timestamp: u64,
+    pub previous_hash: String,
+    pub merkle_root: String,
+    pub nonce: u64,
+    pub difficulty: u32,
+    pub hash: String,
+    // What the hash needs to be recomputed, as in `Block`
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub hash_version: u32,
+    #[serde(default)]
+    pub validator: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl BlockHeader {
+    // Recompute the hash from the header alone. Blocks from before merkle
+    // roots hashed their payload directly, so theirs can't be.
+    pub fn compute_hash(&self) -> String {
+        let block = Block {
+            index: self.index,
+            timestamp: self.timestamp,
+            data: BlockPayload::Text(String::new()),
+            merkle_root: self.merkle_root.clone(),
+            previous_hash: self.previous_hash.clone(),
+            hash: String::new(),
+            nonce: self.nonce,
+            difficulty: self.difficulty,
+            hash_algorithm: self.hash_algorithm,
+            validator: self.validator.clone(),
+            hash_version: self.hash_version,
+            metadata: self.metadata.clone(),
+        };
+        block.compute_hash()
+    }
+
+    // The checks `Blockchain::is_block_valid` makes that need only headers:
+    // the index follows on, the parent hash links up, time doesn't go
+    // backwards, and the hash is genuine and meets the difficulty. What the
+    // merkle root commits to, and whether rewards are right, needs the
+    // payload.
+    pub fn is_valid_after(&self, previous: &BlockHeader, mode: DifficultyMode) -> bool {
+        self.index == previous.index + 1
+            && self.previous_hash == previous.hash
+            && self.timestamp >= previous.timestamp
+            && self.is_hash_valid(mode)
+    }
+
+    // Whether the hash is that of this header and meets its difficulty
+    pub fn is_hash_valid(&self, mode: DifficultyMode) -> bool {
+        !self.merkle_root.is_empty() && self.hash == self.compute_hash() && mode.is_hash_valid(&self.hash, self.difficulty)
+    }
+}
+
+impl Block {
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            index: self.index,
+            timestamp: self.timestamp,
+            previous_hash: self.previous_hash.clone(),
+            merkle_root: self.merkle_root.clone(),
+            nonce: self.nonce,
+            difficulty: self.difficulty,
+            hash: self.hash.clone(),
+            hash_algorithm: self.hash_algorithm,
+            hash_version: self.hash_version,
+            validator: self.validator.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+impl Blockchain {
+    // The header of every block, for peers that only want to follow the
+    // chain. A pruned chain's snapshot stands in for blocks whose headers
+    // are gone, so the headers start after it.
+    pub fn headers(&self) -> Vec<BlockHeader> {
+        let skip = usize::from(matches!(self.chain.first().map(|block| &block.data), Some(BlockPayload::Snapshot(_))));
+        self.chain[skip..].iter().map(Block::header).collect()
+    }
+}
+
+// Check a run of headers from a proof-of-work chain: each one's own hash
+// and difficulty, and that each follows on from the one before. The first
+// is taken as given apart from its hash, as a genesis block would be.
+pub fn verify_header_chain(headers: &[BlockHeader], mode: DifficultyMode) -> bool {
+    match headers.first() {
+        Some(first) if !first.is_hash_valid(mode) => false,
+        _ => headers.windows(2).all(|pair| pair[1].is_valid_after(&pair[0], mode)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_chain_without_payloads() {
+        let mut blockchain = Blockchain::new(2, 100.0);
+        blockchain.add_block("Block 1".to_string()).unwrap();
+        blockchain.mine_pending_transactions("Miner1").unwrap();
+        blockchain.add_block("Block 3".to_string()).unwrap();
+        
+        // Only the headers travel; no transaction data comes with them
+        let json = serde_json::to_string(&blockchain.headers()).unwrap();
+        assert!(!json.contains("Miner1") && !json.contains("Block 1"));
+        let headers: Vec<BlockHeader> = serde_json::from_str(&json).unwrap();
+        assert_eq!(headers.len(), 4);
+        assert!(verify_header_chain(&headers, DifficultyMode::Hex));
+        assert_eq!(headers[2].merkle_root, blockchain.chain[2].data.merkle_root());
+        
+        let mut doctored = headers.clone();
+        doctored[2].nonce += 1;
+        assert!(!verify_header_chain(&doctored, DifficultyMode::Hex));
+        
+        let mut relinked = headers.clone();
+        relinked.remove(1);
+        assert!(!verify_header_chain(&relinked, DifficultyMode::Hex));
+    }
+
+    #[test]
+    fn test_pruned_headers() {
+        let mut blockchain = Blockchain::new(2, 100.0);
+        for i in 1..=4 {
+            blockchain.add_block(format!("Block {}", i)).unwrap();
+        }
+        blockchain.prune_before(2);
+        
+        let headers = blockchain.headers();
+        assert_eq!(headers.first().map(|header| header.index), Some(2));
+        assert!(verify_header_chain(&headers, DifficultyMode::Hex));
+    }
+}
@@ -1,23 +1,56 @@
 use rapid_blockchain::prelude::*;
+use rapid_blockchain::TransactionSigner;
+use ed25519_dalek::SigningKey;
+
+// Fixed demo keys so the example is reproducible; never do this with real funds
+fn demo_key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32])
+}
+
+// The library logs through the `log` crate; print its info lines so the
+// demo shows blocks as they are mined
+struct StdoutLogger;
+
+impl log::Log for StdoutLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+    
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            println!("{}", record.args());
+        }
+    }
+    
+    fn flush(&self) {}
+}
+
+fn signed(key: &SigningKey, recipient: &SigningKey, amount: f64) -> Transaction {
+    let mut transaction = Transaction::new(key.address(), recipient.address(), amount);
+    transaction.sign(key);
+    transaction
+}
 
 fn main() {
+    let _ = log::set_logger(&StdoutLogger);
+    log::set_max_level(log::LevelFilter::Info);
+    
     // Create a new blockchain with difficulty 4 and 100 coins mining reward
     let mut blockchain = Blockchain::new(4, 100.0);
     
     println!("Mining genesis block...");
     
+    let alice = demo_key(1);
+    let bob = demo_key(2);
+    let charlie = demo_key(3);
+    
+    // Alice and Bob need coins before they can send any
+    blockchain.mine_pending_transactions(&alice.address()).unwrap();
+    blockchain.mine_pending_transactions(&bob.address()).unwrap();
+    
     // Add some transactions
-    let tx1 = Transaction::new(
-        String::from("Alice"),
-        String::from("Bob"),
-        50.0
-    );
-    
-    let tx2 = Transaction::new(
-        String::from("Bob"),
-        String::from("Charlie"),
-        25.0
-    );
+    let tx1 = signed(&alice, &bob, 50.0);
+    let tx2 = signed(&bob, &charlie, 25.0);
     
     blockchain.create_transaction(tx1).unwrap();
     blockchain.create_transaction(tx2).unwrap();
@@ -26,20 +59,16 @@ fn main() {
     blockchain.mine_pending_transactions("Miner1").unwrap();
     
     // Create more transactions
-    let tx3 = Transaction::new(
-        String::from("Charlie"),
-        String::from("Alice"),
-        10.0
-    );
+    let tx3 = signed(&charlie, &alice, 10.0);
     
     blockchain.create_transaction(tx3).unwrap();
     blockchain.mine_pending_transactions("Miner1").unwrap();
     
     // Check balance
     println!("Balance of Miner1: {}", blockchain.get_balance_of_address("Miner1"));
-    println!("Balance of Alice: {}", blockchain.get_balance_of_address("Alice"));
-    println!("Balance of Bob: {}", blockchain.get_balance_of_address("Bob"));
-    println!("Balance of Charlie: {}", blockchain.get_balance_of_address("Charlie"));
+    println!("Balance of Alice: {}", blockchain.get_balance_of_address(&alice.address()));
+    println!("Balance of Bob: {}", blockchain.get_balance_of_address(&bob.address()));
+    println!("Balance of Charlie: {}", blockchain.get_balance_of_address(&charlie.address()));
     
     // Validate the chain
     println!("Is blockchain valid? {}", blockchain.is_chain_valid());
@@ -0,0 +1,157 @@
+use crate::{sha256_hex, Transaction};
+
+// Root of a block with no transactions
+pub const EMPTY_MERKLE_ROOT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// Leaf hash of a single transaction
+pub fn transaction_hash(transaction: &Transaction) -> String {
+    let json = serde_json::to_string(transaction).unwrap_or_default();
+    sha256_hex(json.as_bytes())
+}
+
+// Combine two child hashes into their parent
+fn hash_pair(left: &str, right: &str) -> String {
+    sha256_hex(format!("{}{}", left, right).as_bytes())
+}
+
+// Build the SHA-256 Merkle tree over the transactions and return its root.
+// A level with an odd number of nodes pairs its last node with itself.
+pub fn merkle_root(transactions: &[Transaction]) -> String {
+    if transactions.is_empty() {
+        return EMPTY_MERKLE_ROOT.to_string();
+    }
+    
+    let mut level: Vec<String> = transactions.iter().map(transaction_hash).collect();
+    
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+    }
+    
+    level.remove(0)
+}
+
+// Sibling hashes on the path from the transaction at `index` up to the root,
+// lowest level first. None if there is no transaction at `index`.
+pub fn merkle_proof(transactions: &[Transaction], index: usize) -> Option<Vec<String>> {
+    if index >= transactions.len() {
+        return None;
+    }
+    
+    let mut level: Vec<String> = transactions.iter().map(transaction_hash).collect();
+    let mut position = index;
+    let mut proof = Vec::new();
+    
+    while level.len() > 1 {
+        let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+        proof.push(sibling.clone());
+        
+        level = level
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+            .collect();
+        position /= 2;
+    }
+    
+    Some(proof)
+}
+
+// Check that `leaf` sits at `index` in the tree with the given root. The
+// index tells which side each sibling in the proof is on.
+pub fn verify_merkle_proof(leaf: &str, index: usize, proof: &[String], root: &str) -> bool {
+    let mut hash = leaf.to_string();
+    let mut position = index;
+    
+    for sibling in proof {
+        hash = if position.is_multiple_of(2) {
+            hash_pair(&hash, sibling)
+        } else {
+            hash_pair(sibling, &hash)
+        };
+        position /= 2;
+    }
+    
+    hash == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(amount: f64) -> Transaction {
+        Transaction::new("Alice".to_string(), "Bob".to_string(), amount)
+    }
+
+    #[test]
+    fn test_empty_root() {
+        assert_eq!(merkle_root(&[]), EMPTY_MERKLE_ROOT);
+    }
+
+    #[test]
+    fn test_single_leaf_is_its_own_root() {
+        let transactions = vec![tx(10.0)];
+        assert_eq!(merkle_root(&transactions), transaction_hash(&transactions[0]));
+    }
+
+    #[test]
+    fn test_odd_leaf_count_duplicates_last() {
+        let transactions = vec![tx(1.0), tx(2.0), tx(3.0)];
+        let leaves: Vec<String> = transactions.iter().map(transaction_hash).collect();
+        
+        let expected = hash_pair(
+            &hash_pair(&leaves[0], &leaves[1]),
+            &hash_pair(&leaves[2], &leaves[2]),
+        );
+        assert_eq!(merkle_root(&transactions), expected);
+    }
+
+    #[test]
+    fn test_flipping_amount_changes_root() {
+        let mut transactions = vec![tx(1.0), tx(2.0), tx(3.0), tx(4.0)];
+        let original_root = merkle_root(&transactions);
+        
+        transactions[2].amount = 30.0;
+        assert_ne!(merkle_root(&transactions), original_root);
+    }
+
+    #[test]
+    fn test_order_matters() {
+        let transactions = vec![tx(1.0), tx(2.0)];
+        let swapped = vec![tx(2.0), tx(1.0)];
+        assert_ne!(merkle_root(&transactions), merkle_root(&swapped));
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_leaf() {
+        let transactions: Vec<Transaction> = (1..=5).map(|i| tx(i as f64)).collect();
+        let root = merkle_root(&transactions);
+        
+        for (index, transaction) in transactions.iter().enumerate() {
+            let proof = merkle_proof(&transactions, index).unwrap();
+            assert_eq!(proof.len(), 3);
+            assert!(verify_merkle_proof(&transaction_hash(transaction), index, &proof, &root));
+        }
+    }
+
+    #[test]
+    fn test_proof_rejects_wrong_leaf_or_position() {
+        let transactions: Vec<Transaction> = (1..=4).map(|i| tx(i as f64)).collect();
+        let root = merkle_root(&transactions);
+        let proof = merkle_proof(&transactions, 1).unwrap();
+        
+        assert!(!verify_merkle_proof(&transaction_hash(&tx(99.0)), 1, &proof, &root));
+        assert!(!verify_merkle_proof(&transaction_hash(&transactions[1]), 2, &proof, &root));
+        assert!(merkle_proof(&transactions, 4).is_none());
+    }
+
+    #[test]
+    fn test_single_leaf_proof_is_empty() {
+        let transactions = vec![tx(1.0)];
+        let proof = merkle_proof(&transactions, 0).unwrap();
+        
+        assert!(proof.is_empty());
+        assert!(verify_merkle_proof(&transaction_hash(&transactions[0]), 0, &proof, &merkle_root(&transactions)));
+    }
+}
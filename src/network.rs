@@ -0,0 +1,307 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use tokio::task::JoinSet;
+
+use crate::{lock_blockchain as lock, Block, Blockchain};
+
+// How long to wait on a peer before giving up on it
+const PEER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long `ping_nodes` gives each node to answer
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The part of a peer's `GET /chain` response that consensus needs
+#[derive(Deserialize)]
+struct ChainResponse {
+    chain: Vec<Block>,
+}
+
+impl Blockchain {
+    // Fetch the chain of every active node, ready for `resolve_conflicts`.
+    // Peers that can't be reached or don't send a chain are skipped.
+    pub async fn fetch_peer_chains(&self) -> Vec<Vec<Block>> {
+        fetch_chains(self.active_nodes()).await
+            .into_iter()
+            .filter_map(|(_, chain)| chain)
+            .collect()
+    }
+
+    // Fetch chains from the active nodes and adopt the heaviest valid one,
+    // as `resolve_conflicts` does. Nodes that didn't answer are marked
+    // inactive; `set_node_active` brings them back.
+    pub async fn sync_with_peers(&mut self) -> Option<u128> {
+        let fetched = fetch_chains(self.active_nodes()).await;
+        self.adopt_fetched(fetched)
+    }
+    
+    // Mark nodes whose chain couldn't be fetched inactive, and adopt the
+    // heaviest of the rest
+    fn adopt_fetched(&mut self, fetched: Vec<(String, Option<Vec<Block>>)>) -> Option<u128> {
+        let mut node_chains = Vec::new();
+        
+        for (node, chain) in fetched {
+            match chain {
+                Some(chain) => node_chains.push((node, chain)),
+                None => self.set_node_active(&node, false),
+            }
+        }
+        
+        self.resolve_conflicts_from(node_chains)
+    }
+    
+    // POST `block` to `/blocks` on every active node, so peers hear about it
+    // without waiting for their next sync. Returns how many accepted it.
+    pub async fn broadcast_block(&self, block: &Block) -> usize {
+        let nodes = self.active_nodes().into_iter().cloned().collect();
+        post_block(nodes, block.clone()).await
+    }
+    
+    // Check every registered node, active or not, and mark each active if
+    // and only if it answers within `PING_TIMEOUT`
+    pub async fn ping_nodes(&mut self) {
+        self.ping_nodes_with_timeout(PING_TIMEOUT).await;
+    }
+    
+    // Like `ping_nodes`, giving each node `timeout` to answer. Nodes are
+    // pinged concurrently, so one slow node doesn't hold up the rest.
+    pub async fn ping_nodes_with_timeout(&mut self, timeout: Duration) {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+        let mut pings = JoinSet::new();
+        
+        for node in self.nodes.keys() {
+            let (client, node) = (client.clone(), node.clone());
+            pings.spawn(async move {
+                let is_active = ping(&client, &node).await;
+                (node, is_active)
+            });
+        }
+        
+        while let Some(result) = pings.join_next().await {
+            if let Ok((node, is_active)) = result {
+                self.set_node_active(&node, is_active);
+            }
+        }
+    }
+}
+
+// Whether anything answers HTTP at `node`. Any response counts, even an
+// error status: the node is up, whatever it thinks of the request.
+async fn ping(client: &reqwest::Client, node: &str) -> bool {
+    client.head(node).send().await.is_ok()
+}
+
+// `sync_with_peers` for a blockchain shared between tasks. The lock is
+// only held while not waiting on the network.
+pub async fn sync_shared(blockchain: &Mutex<Blockchain>) -> Option<u128> {
+    let nodes: Vec<String> = lock(blockchain).active_nodes().into_iter().cloned().collect();
+    let fetched = fetch_chains(nodes.iter().collect()).await;
+    lock(blockchain).adopt_fetched(fetched)
+}
+
+// Send `block` to all of `nodes` at once and count the ones that took it
+pub(crate) async fn post_block(nodes: Vec<String>, block: Block) -> usize {
+    let client = reqwest::Client::builder()
+        .timeout(PEER_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let block = Arc::new(block);
+    let mut posts = JoinSet::new();
+    
+    for node in nodes {
+        let (client, block) = (client.clone(), block.clone());
+        posts.spawn(async move {
+            let url = format!("{}/blocks", node.trim_end_matches('/'));
+            let result = client.post(url).json(&*block).send().await.and_then(|response| response.error_for_status());
+            if let Err(e) = &result {
+                log::warn!("Could not send block {} to {}: {}", block.index, node, e);
+            }
+            result.is_ok()
+        });
+    }
+    
+    let mut accepted = 0;
+    while let Some(result) = posts.join_next().await {
+        if let Ok(true) = result {
+            accepted += 1;
+        }
+    }
+    accepted
+}
+
+// Each node's chain, or `None` if it couldn't be fetched
+async fn fetch_chains(nodes: Vec<&String>) -> Vec<(String, Option<Vec<Block>>)> {
+    let client = reqwest::Client::builder()
+        .timeout(PEER_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    let mut chains = Vec::with_capacity(nodes.len());
+    
+    for node in nodes {
+        let chain = match fetch_chain(&client, node).await {
+            Ok(chain) => Some(chain),
+            Err(e) => {
+                log::warn!("Could not fetch the chain from {}: {}", node, e);
+                None
+            }
+        };
+        chains.push((node.clone(), chain));
+    }
+    
+    chains
+}
+
+async fn fetch_chain(client: &reqwest::Client, node: &str) -> Result<Vec<Block>, reqwest::Error> {
+    let url = format!("{}/chain", node.trim_end_matches('/'));
+    let response: ChainResponse = client.get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    
+    Ok(response.chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // A peer that answers every request with `blockchain` as JSON
+    async fn serve_chain(blockchain: &Blockchain) -> String {
+        serve_body(serde_json::to_string(blockchain).unwrap()).await
+    }
+
+    // A peer that answers every request with `body`
+    async fn serve_body(body: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buffer[..n]),
+                    }
+                }
+                
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(), body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        
+        address
+    }
+
+    // An address nothing is listening on
+    fn unreachable_node() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_peer_chains() {
+        let mut blockchain = Blockchain::new(2, 100.0);
+        let mut peer = blockchain.clone();
+        peer.add_block("Block 1".to_string()).unwrap();
+        
+        blockchain.register_node(serve_chain(&peer).await);
+        blockchain.register_node(unreachable_node());
+        
+        let chains = blockchain.fetch_peer_chains().await;
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].len(), 2);
+        assert_eq!(chains[0][1].hash, peer.chain[1].hash);
+        
+        // Fetching alone changes nothing
+        assert_eq!(blockchain.active_nodes().len(), 2);
+        assert_eq!(blockchain.chain.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sync_with_peers() {
+        let mut blockchain = Blockchain::new(2, 100.0);
+        let mut peer = blockchain.clone();
+        peer.add_block("Block 1".to_string()).unwrap();
+        
+        let (reachable, unreachable) = (serve_chain(&peer).await, unreachable_node());
+        blockchain.register_node(reachable.clone());
+        blockchain.register_node(unreachable);
+        
+        assert!(blockchain.sync_with_peers().await.is_some());
+        assert_eq!(blockchain.chain.len(), 2);
+        assert_eq!(blockchain.active_nodes(), vec![&reachable]);
+        
+        // Nothing heavier on offer the second time round
+        assert_eq!(blockchain.sync_with_peers().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_sync_skips_peers_without_a_chain() {
+        let mut blockchain = Blockchain::new(2, 100.0);
+        let mut peer = blockchain.clone();
+        peer.add_block("Block 1".to_string()).unwrap();
+        peer.add_block("Block 2".to_string()).unwrap();
+        
+        // One peer answers, but not with a chain; the sync carries on past it
+        let garbled = serve_body("{\"chain\": \"not blocks\"}".to_string()).await;
+        let reachable = serve_chain(&peer).await;
+        blockchain.register_node(garbled);
+        blockchain.register_node(reachable.clone());
+        
+        assert!(blockchain.sync_with_peers().await.is_some());
+        assert_eq!(blockchain.chain.len(), 3);
+        assert_eq!(blockchain.active_nodes(), vec![&reachable]);
+    }
+
+    // A node that accepts connections but never answers
+    async fn silent_node() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                connections.push(stream);
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_ping_nodes() {
+        let mut blockchain = Blockchain::new(2, 100.0);
+        let peer = blockchain.clone();
+        let (reachable, unreachable, silent) = (serve_chain(&peer).await, unreachable_node(), silent_node().await);
+        blockchain.register_node(reachable.clone());
+        blockchain.register_node(unreachable);
+        blockchain.register_node(silent);
+        
+        // The node that never answers costs one timeout, not one per node
+        let started = std::time::Instant::now();
+        blockchain.ping_nodes_with_timeout(Duration::from_millis(500)).await;
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(blockchain.active_nodes(), vec![&reachable]);
+        
+        assert_eq!(blockchain.prune_inactive(), 2);
+        assert_eq!(blockchain.nodes.len(), 1);
+        assert_eq!(blockchain.prune_inactive(), 0);
+        
+        // A node that comes back is marked active again
+        blockchain.set_node_active(&reachable, false);
+        blockchain.ping_nodes().await;
+        assert_eq!(blockchain.active_nodes(), vec![&reachable]);
+    }
+}
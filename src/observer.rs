@@ -0,0 +1,117 @@
+use std::fmt;
+
+use crate::{Block, Transaction};
+
+// Told about chain events as they happen, so callers can react without
+// polling. Register one with `Blockchain::add_observer`.
+//
+// Callbacks run synchronously on whichever thread mined the block or
+// submitted the transaction, while the chain is borrowed mutably (or its
+// lock held, for a shared chain), so keep them short and hand slow work
+// off elsewhere. Observers must be `Send + Sync` because a blockchain can
+// move between threads; shared state needs an atomic or a lock.
+pub trait BlockchainObserver: Send + Sync {
+    // A block was mined onto the chain, or received from a peer
+    fn on_block_added(&self, _block: &Block) {}
+    
+    // A transaction was accepted into the mempool
+    fn on_transaction_received(&self, _transaction: &Transaction) {}
+}
+
+// The registered observers. They belong to this copy of the chain, so a
+// clone starts without any.
+#[derive(Default)]
+pub(crate) struct Observers(Vec<Box<dyn BlockchainObserver>>);
+
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Box<dyn BlockchainObserver>) {
+        self.0.push(observer);
+    }
+    
+    pub(crate) fn block_added(&self, block: &Block) {
+        for observer in &self.0 {
+            observer.on_block_added(block);
+        }
+    }
+    
+    pub(crate) fn transaction_received(&self, transaction: &Transaction) {
+        for observer in &self.0 {
+            observer.on_transaction_received(transaction);
+        }
+    }
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Observers {
+        Observers::default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} observers", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Blockchain, TransactionSigner};
+    use ed25519_dalek::SigningKey;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter {
+        blocks: AtomicUsize,
+        transactions: AtomicUsize,
+    }
+
+    // Observers are boxed, so the test keeps its own handle on the counts
+    struct Counting(Arc<Counter>);
+    
+    impl BlockchainObserver for Counting {
+        fn on_block_added(&self, _block: &Block) {
+            self.0.blocks.fetch_add(1, Ordering::SeqCst);
+        }
+        
+        fn on_transaction_received(&self, _transaction: &Transaction) {
+            self.0.transactions.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_observers_are_told() {
+        let counter = Arc::new(Counter::default());
+        let mut blockchain = Blockchain::new(1, 100.0);
+        blockchain.add_observer(Box::new(Counting(counter.clone())));
+        
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        blockchain.add_block("Block 1".to_string()).unwrap();
+        blockchain.mine_pending_transactions(&alice.address()).unwrap();
+        assert_eq!(counter.blocks.load(Ordering::SeqCst), 2);
+        
+        let mut transaction = Transaction::new(alice.address(), "Bob".to_string(), 10.0);
+        transaction.sign(&alice);
+        blockchain.create_transaction(transaction.clone()).unwrap();
+        // Rejected transactions aren't reported
+        assert!(blockchain.create_transaction(transaction).is_err());
+        assert_eq!(counter.transactions.load(Ordering::SeqCst), 1);
+        
+        // A batch is reported once all of it is accepted, and not at all
+        // if any of it is rejected
+        let paying = |amount: f64| {
+            let mut transaction = Transaction::new(alice.address(), "Carol".to_string(), amount);
+            transaction.sign(&alice);
+            transaction
+        };
+        assert!(blockchain.create_transactions(vec![paying(20.0), paying(500.0)]).is_err());
+        assert_eq!(counter.transactions.load(Ordering::SeqCst), 1);
+        blockchain.create_transactions(vec![paying(20.0), paying(30.0)]).unwrap();
+        assert_eq!(counter.transactions.load(Ordering::SeqCst), 3);
+        
+        // A copy of the chain doesn't report to the original's observers
+        blockchain.clone().add_block("Block 3".to_string()).unwrap();
+        assert_eq!(counter.blocks.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::{handle_get_chain, lock_blockchain as lock, Block, Blockchain, BlockchainError, ReceivedBlock, Transaction};
+
+type Shared = Arc<Mutex<Blockchain>>;
+
+// The node's HTTP API. Every response is JSON; failures carry an `error`
+// message and a 4xx or 5xx status.
+//   GET  /chain              the whole blockchain
+//   POST /transactions       submit a signed transaction (JSON body)
+//   POST /blocks             a block announced by a peer (JSON body)
+//   POST /mine?miner=...     mine pending transactions, rewarding `miner`
+//   POST /mine/{address}     the same, with the miner in the path
+//   GET  /balance/{address}  an address's balance
+pub fn router(blockchain: Shared) -> Router {
+    Router::new()
+        .route("/chain", get(get_chain))
+        .route("/transactions", post(new_transaction))
+        .route("/blocks", post(new_block))
+        .route("/mine", post(mine_for_query))
+        .route("/mine/{address}", post(mine_for_path))
+        .route("/balance/{address}", get(balance))
+        .with_state(blockchain)
+}
+
+// Serve the API on `listener` until the process stops
+pub async fn serve(listener: TcpListener, blockchain: Shared) -> std::io::Result<()> {
+    axum::serve(listener, router(blockchain)).await
+}
+
+// Bind `addr` and serve the API there until the process stops
+pub async fn run_server(blockchain: Shared, addr: impl ToSocketAddrs) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Node listening on {}", listener.local_addr()?);
+    serve(listener, blockchain).await
+}
+
+#[derive(Deserialize)]
+struct MineQuery {
+    miner: String,
+}
+
+// Rejected input is the client's fault; anything else is the node's
+fn error_response(e: BlockchainError) -> Response {
+    let status = match e {
+        BlockchainError::DuplicateTransaction(_) => StatusCode::CONFLICT,
+        BlockchainError::InvalidTransaction(_)
+        | BlockchainError::InsufficientFunds { .. }
+        | BlockchainError::BadNonce { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        BlockchainError::InvalidBlock => StatusCode::UNPROCESSABLE_ENTITY,
+        BlockchainError::NoValidator => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(json!({ "error": e.to_string() }))).into_response()
+}
+
+async fn get_chain(State(blockchain): State<Shared>) -> impl IntoResponse {
+    let json = handle_get_chain(&lock(&blockchain));
+    ([(header::CONTENT_TYPE, "application/json")], json)
+}
+
+async fn new_transaction(State(blockchain): State<Shared>, Json(transaction): Json<Transaction>) -> Response {
+    match lock(&blockchain).create_transaction(transaction) {
+        Ok(()) => (
+            StatusCode::CREATED,
+            Json(json!({ "message": "Transaction added to pending transactions" })),
+        ).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+// With the `networking` feature, a block from further ahead sends us to
+// our peers for the blocks in between
+async fn new_block(State(blockchain): State<Shared>, Json(block): Json<Block>) -> Response {
+    let index = block.index;
+    let received = lock(&blockchain).receive_block(block);
+    
+    match received {
+        Ok(ReceivedBlock::Added) => (
+            StatusCode::CREATED,
+            Json(json!({ "message": format!("Block {} added", index) })),
+        ).into_response(),
+        Ok(ReceivedBlock::Ahead) => {
+            #[cfg(feature = "networking")]
+            tokio::spawn(async move { crate::network::sync_shared(&blockchain).await });
+            (
+                StatusCode::ACCEPTED,
+                Json(json!({ "message": format!("Block {} is ahead of our chain", index) })),
+            ).into_response()
+        }
+        Ok(ReceivedBlock::Behind) => Json(json!({ "message": format!("Already past block {}", index) })).into_response(),
+        Ok(ReceivedBlock::Fork { competing_hash }) => (
+            StatusCode::ACCEPTED,
+            Json(json!({ "message": format!("Block {} is on a fork", index), "competing_hash": competing_hash })),
+        ).into_response(),
+        Ok(ReceivedBlock::Reorganized { replaced }) => (
+            StatusCode::CREATED,
+            Json(json!({ "message": format!("Block {} added, replacing {} of our blocks", index, replaced) })),
+        ).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn mine_for_query(State(blockchain): State<Shared>, Query(query): Query<MineQuery>) -> Response {
+    mine(blockchain, query.miner).await
+}
+
+async fn mine_for_path(State(blockchain): State<Shared>, Path(address): Path<String>) -> Response {
+    mine(blockchain, address).await
+}
+
+// Mining is CPU-bound, so keep it off the async worker threads. The chain
+// is only locked to take a template and to append the result, so other
+// requests are served while the block is mined. With the `networking`
+// feature, the new block is then announced to active nodes.
+async fn mine(blockchain: Shared, miner: String) -> Response {
+    let message = format!("Block mined successfully. Reward sent to {}", miner);
+    let mined = tokio::task::spawn_blocking(move || -> Result<(Block, Vec<String>), BlockchainError> {
+        loop {
+            let template = lock(&blockchain).block_template(&miner)?;
+            let previous_hash = template.previous_hash.clone();
+            let block = template.mine();
+            
+            // A peer's block may have taken its place meanwhile; if so,
+            // start again on top of it
+            let mut blockchain = lock(&blockchain);
+            if blockchain.get_latest_block().is_none_or(|latest| latest.hash != previous_hash) {
+                continue;
+            }
+            blockchain.append_validated_block(block.clone())?;
+            return Ok((block, blockchain.active_nodes().into_iter().cloned().collect()));
+        }
+    }).await;
+    
+    match mined {
+        Ok(Ok((block, nodes))) => {
+            #[cfg(feature = "networking")]
+            tokio::spawn(crate::network::post_block(nodes, block.clone()));
+            #[cfg(not(feature = "networking"))]
+            drop(nodes);
+            Json(json!({
+                "message": message,
+                "block": block,
+            })).into_response()
+        }
+        Ok(Err(e)) => error_response(e),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": format!("Error mining block: {}", e) })),
+        ).into_response(),
+    }
+}
+
+async fn balance(State(blockchain): State<Shared>, Path(address): Path<String>) -> Json<Value> {
+    let balance = lock(&blockchain).get_balance_of_address(&address);
+    Json(json!({ "address": address, "balance": balance }))
+}
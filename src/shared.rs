@@ -0,0 +1,84 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{Block, Blockchain, BlockchainError, Transaction};
+
+// One blockchain shared between threads. Cloning a `SharedBlockchain`
+// gives another handle on the same chain, unlike cloning a `Blockchain`,
+// which copies it; each method takes the lock for just as long as it
+// needs. Mining holds the write lock throughout, so two miners take turns
+// rather than racing to extend the same tip.
+#[derive(Clone)]
+pub struct SharedBlockchain(Arc<RwLock<Blockchain>>);
+
+impl SharedBlockchain {
+    pub fn new(blockchain: Blockchain) -> Self {
+        SharedBlockchain(Arc::new(RwLock::new(blockchain)))
+    }
+    
+    // Poisoning is ignored, as in the server: the call that panicked may
+    // be half done, but blocks only ever go onto the chain whole
+    pub fn read(&self) -> RwLockReadGuard<'_, Blockchain> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    pub fn write(&self) -> RwLockWriteGuard<'_, Blockchain> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+    
+    pub fn add_block(&self, data: String) -> Result<Block, BlockchainError> {
+        self.write().add_block(data).cloned()
+    }
+    
+    pub fn create_transaction(&self, transaction: Transaction) -> Result<(), BlockchainError> {
+        self.write().create_transaction(transaction)
+    }
+    
+    pub fn mine_pending_transactions(&self, miner_address: &str) -> Result<Block, BlockchainError> {
+        self.write().mine_pending_transactions(miner_address).cloned()
+    }
+    
+    // A copy of the chain as it stands, to read without holding the lock
+    pub fn snapshot(&self) -> Blockchain {
+        self.read().clone()
+    }
+}
+
+impl From<Blockchain> for SharedBlockchain {
+    fn from(blockchain: Blockchain) -> Self {
+        SharedBlockchain::new(blockchain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_two_miners_share_one_chain() {
+        let shared = SharedBlockchain::new(Blockchain::new(2, 100.0));
+        
+        let miners: Vec<_> = ["Miner1", "Miner2"].into_iter().map(|miner| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    shared.mine_pending_transactions(miner).unwrap();
+                }
+            })
+        }).collect();
+        for miner in miners {
+            miner.join().unwrap();
+        }
+        
+        // Every block from both miners landed on the one chain
+        let blockchain = shared.snapshot();
+        assert_eq!(blockchain.chain.len(), 11);
+        assert!(blockchain.is_chain_valid());
+        assert_eq!(blockchain.balance("Miner1"), 500.0);
+        assert_eq!(blockchain.balance("Miner2"), 500.0);
+        
+        let block = shared.add_block("After".to_string()).unwrap();
+        assert_eq!(block.index, 11);
+        assert_eq!(blockchain.chain.len(), 11);
+    }
+}
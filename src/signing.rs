@@ -0,0 +1,106 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+#[cfg(feature = "crypto")]
+use sha2::{Digest, Sha256};
+
+// A private key that can sign transactions for the address it controls.
+// Implemented for Ed25519 keys, and for secp256k1 keys with the `crypto`
+// feature.
+pub trait TransactionSigner {
+    // The address this key signs for: its hex-encoded public key
+    fn address(&self) -> String;
+    
+    // Raw signature bytes over `message`
+    fn sign_message(&self, message: &[u8]) -> Vec<u8>;
+}
+
+impl TransactionSigner for SigningKey {
+    fn address(&self) -> String {
+        hex::encode(self.verifying_key().as_bytes())
+    }
+    
+    fn sign_message(&self, message: &[u8]) -> Vec<u8> {
+        self.sign(message).to_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "crypto")]
+impl TransactionSigner for secp256k1::SecretKey {
+    fn address(&self) -> String {
+        hex::encode(secp256k1::PublicKey::from_secret_key_global(self).serialize())
+    }
+    
+    fn sign_message(&self, message: &[u8]) -> Vec<u8> {
+        self.sign_ecdsa(ecdsa_message(message)).serialize_compact().to_vec()
+    }
+}
+
+// ECDSA signs a 32-byte digest rather than the message itself
+#[cfg(feature = "crypto")]
+pub(crate) fn ecdsa_message(message: &[u8]) -> secp256k1::Message {
+    secp256k1::Message::from_digest(Sha256::digest(message).into())
+}
+
+// Verify `signature` over `message`. The scheme follows from the key: 32
+// bytes is Ed25519, 33 bytes is a compressed secp256k1 key.
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    match public_key.len() {
+        32 => verify_ed25519(public_key, message, signature),
+        #[cfg(feature = "crypto")]
+        33 => match secp256k1::PublicKey::from_slice(public_key) {
+            Ok(public_key) => verify_ecdsa(&public_key, message, signature),
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+fn verify_ed25519(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(verifying_key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    
+    verifying_key.verify(message, &signature).is_ok()
+}
+
+#[cfg(feature = "crypto")]
+pub(crate) fn verify_ecdsa(public_key: &secp256k1::PublicKey, message: &[u8], signature: &[u8]) -> bool {
+    let Ok(signature) = secp256k1::ecdsa::Signature::from_compact(signature) else {
+        return false;
+    };
+    
+    signature.verify(&ecdsa_message(message), public_key).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ed25519_round_trip() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let signature = key.sign_message(b"hello");
+        let public_key = hex::decode(key.address()).unwrap();
+        
+        assert!(verify(&public_key, b"hello", &signature));
+        assert!(!verify(&public_key, b"hellO", &signature));
+        assert!(!verify(&public_key[..31], b"hello", &signature));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_secp256k1_round_trip() {
+        let key = secp256k1::SecretKey::from_byte_array(&[1; 32]).unwrap();
+        let signature = key.sign_message(b"hello");
+        let public_key = hex::decode(key.address()).unwrap();
+        
+        assert_eq!(public_key.len(), 33);
+        assert!(verify(&public_key, b"hello", &signature));
+        assert!(!verify(&public_key, b"hellO", &signature));
+    }
+}
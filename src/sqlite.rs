@@ -0,0 +1,181 @@
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{Block, Blockchain, BlockchainError};
+
+// One row per block, keyed by index, with a unique (so indexed) hash.
+// Everything else about the blockchain lives in a single `state` row.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS blocks (
+        block_index INTEGER PRIMARY KEY,
+        hash TEXT NOT NULL UNIQUE,
+        block TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS state (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        blockchain TEXT NOT NULL
+    );
+";
+
+fn open(path: &str) -> Result<Connection, BlockchainError> {
+    let connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    Ok(connection)
+}
+
+fn insert_block(connection: &Connection, block: &Block) -> Result<(), BlockchainError> {
+    connection.execute(
+        "INSERT INTO blocks (block_index, hash, block) VALUES (?1, ?2, ?3)",
+        params![block.index, block.hash, serde_json::to_string(block)?],
+    )?;
+    Ok(())
+}
+
+impl Blockchain {
+    // The blockchain as JSON, minus its blocks
+    fn state_json(&self) -> Result<String, BlockchainError> {
+        let mut state = serde_json::to_value(self)?;
+        if let Some(object) = state.as_object_mut() {
+            object.remove("chain");
+        }
+        Ok(state.to_string())
+    }
+
+    fn write_state(&self, connection: &Connection) -> Result<(), BlockchainError> {
+        connection.execute(
+            "INSERT OR REPLACE INTO state (id, blockchain) VALUES (0, ?1)",
+            params![self.state_json()?],
+        )?;
+        Ok(())
+    }
+
+    // Save the whole blockchain to a SQLite database, replacing its contents
+    pub fn save_to_db(&self, path: &str) -> Result<(), BlockchainError> {
+        let mut connection = open(path)?;
+        let transaction = connection.transaction()?;
+        
+        transaction.execute("DELETE FROM blocks", [])?;
+        for block in &self.chain {
+            insert_block(&transaction, block)?;
+        }
+        self.write_state(&transaction)?;
+        
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Add one newly mined block to a database written by `save_to_db`,
+    // without rewriting the blocks already there. The rest of the state
+    // (pending transactions and so on) is refreshed too.
+    pub fn append_block_to_db(&self, block: &Block, path: &str) -> Result<(), BlockchainError> {
+        let mut connection = open(path)?;
+        let transaction = connection.transaction()?;
+        
+        insert_block(&transaction, block)?;
+        self.write_state(&transaction)?;
+        
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Load a blockchain saved with `save_to_db`
+    pub fn load_from_db(path: &str) -> Result<Blockchain, BlockchainError> {
+        if !Path::new(path).exists() {
+            return Err(BlockchainError::FileNotFound(path.to_string()));
+        }
+        
+        let connection = open(path)?;
+        let state: String = connection.query_row("SELECT blockchain FROM state WHERE id = 0", [], |row| row.get(0))?;
+        let mut blockchain: serde_json::Value = serde_json::from_str(&state)?;
+        
+        let mut statement = connection.prepare("SELECT block FROM blocks ORDER BY block_index")?;
+        let chain = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|json| Ok(serde_json::from_str(&json?)?))
+            .collect::<Result<Vec<serde_json::Value>, BlockchainError>>()?;
+        
+        blockchain["chain"] = serde_json::Value::Array(chain);
+        let mut blockchain: Blockchain = serde_json::from_value(blockchain)?;
+        blockchain.rebuild_indexes();
+        
+        Ok(blockchain)
+    }
+
+    // Look up a single saved block by its hash
+    pub fn load_block_from_db(path: &str, hash: &str) -> Result<Option<Block>, BlockchainError> {
+        let connection = open(path)?;
+        let json: Option<String> = connection
+            .query_row("SELECT block FROM blocks WHERE hash = ?1", params![hash], |row| row.get(0))
+            .optional()?;
+        
+        match json {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Blockchain;
+    use std::fs;
+
+    #[test]
+    fn test_db_round_trip() {
+        let path = "test_db_round_trip.sqlite";
+        let _ = fs::remove_file(path);
+        
+        let mut blockchain = Blockchain::new(2, 100.0);
+        blockchain.add_block("Block 1".to_string()).unwrap();
+        blockchain.mine_pending_transactions("Miner1").unwrap();
+        blockchain.save_to_db(path).unwrap();
+        
+        let loaded = Blockchain::load_from_db(path).unwrap();
+        assert_eq!(loaded.chain.len(), 3);
+        assert_eq!(loaded.chain[2].hash, blockchain.chain[2].hash);
+        assert_eq!(loaded.get_balance_of_address("Miner1"), 100.0);
+        assert_eq!(loaded.balance("Miner1"), 100.0);
+        assert!(loaded.is_chain_valid());
+        
+        // Saving again replaces rather than duplicates
+        blockchain.save_to_db(path).unwrap();
+        assert_eq!(Blockchain::load_from_db(path).unwrap().chain.len(), 3);
+        
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_db_append_and_lookup() {
+        let path = "test_db_append.sqlite";
+        let _ = fs::remove_file(path);
+        
+        let mut blockchain = Blockchain::new(2, 100.0);
+        blockchain.save_to_db(path).unwrap();
+        
+        blockchain.add_block("Block 1".to_string()).unwrap();
+        let block = blockchain.get_latest_block().unwrap().clone();
+        blockchain.append_block_to_db(&block, path).unwrap();
+        
+        let loaded = Blockchain::load_from_db(path).unwrap();
+        assert_eq!(loaded.chain.len(), 2);
+        assert!(loaded.is_chain_valid());
+        
+        let found = Blockchain::load_block_from_db(path, &block.hash).unwrap().unwrap();
+        assert_eq!(found.index, 1);
+        assert!(Blockchain::load_block_from_db(path, "missing").unwrap().is_none());
+        
+        // The same block can't be appended twice
+        assert!(blockchain.append_block_to_db(&block, path).is_err());
+        
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_load_missing_db() {
+        assert!(matches!(
+            Blockchain::load_from_db("does_not_exist.sqlite"),
+            Err(crate::BlockchainError::FileNotFound(_))
+        ));
+    }
+}
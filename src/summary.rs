@@ -0,0 +1,94 @@
+use serde::Serialize;
+
+use crate::Blockchain;
+
+// The figures a block explorer shows at the top of the page, gathered in
+// one pass so they all describe the same moment
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChainSummary {
+    pub height: u32,
+    // Hash of the latest block; empty for an empty chain
+    pub tip_hash: String,
+    // Confirmed transactions, mining rewards included
+    pub total_transactions: usize,
+    pub total_supply: f64,
+    // The difficulty the next block will be mined at
+    pub difficulty: u32,
+    // Mean seconds between the last `retarget_window` blocks, or None
+    // until two blocks have been mined after genesis
+    pub average_block_time: Option<f64>,
+    pub mempool_size: usize,
+}
+
+impl Blockchain {
+    // Height, supply, difficulty and the rest in one serializable struct
+    pub fn summary(&self) -> ChainSummary {
+        ChainSummary {
+            height: self.height(),
+            tip_hash: self.get_latest_block().map(|block| block.hash.clone()).unwrap_or_default(),
+            total_transactions: self.chain.iter().map(|block| block.data.transactions().len()).sum(),
+            total_supply: self.total_supply(),
+            difficulty: self.next_difficulty(),
+            average_block_time: self.average_block_time(),
+            mempool_size: self.mempool_size(),
+        }
+    }
+
+    // Like `next_difficulty`, leave out the interval after the pinned
+    // genesis timestamp
+    fn average_block_time(&self) -> Option<f64> {
+        let intervals = self.retarget_window.min(self.chain.len().saturating_sub(2));
+        if intervals == 0 {
+            return None;
+        }
+        
+        let newest = &self.chain[self.chain.len() - 1];
+        let oldest = &self.chain[self.chain.len() - 1 - intervals];
+        Some(newest.timestamp.saturating_sub(oldest.timestamp) as f64 / intervals as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, TransactionSigner};
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_summary() {
+        let mut blockchain = Blockchain::builder().retarget_window(2).build();
+        let empty = blockchain.summary();
+        assert_eq!(empty.height, 0);
+        assert_eq!(empty.tip_hash, blockchain.chain[0].hash);
+        assert_eq!(empty.average_block_time, None);
+        
+        let miner = SigningKey::from_bytes(&[1; 32]);
+        for _ in 0..3 {
+            blockchain.mine_pending_transactions(&miner.address()).unwrap();
+        }
+        let mut transaction = Transaction::new(miner.address(), "Bob".to_string(), 10.0);
+        transaction.sign(&miner);
+        blockchain.create_transaction(transaction).unwrap();
+        
+        // Only the spacing of the last two intervals counts
+        for (block, timestamp) in blockchain.chain[1..].iter_mut().zip([1_000, 1_100, 1_130]) {
+            block.timestamp = timestamp;
+        }
+        
+        let summary = blockchain.summary();
+        assert_eq!(summary.height, 3);
+        assert_eq!(summary.tip_hash, blockchain.chain[3].hash);
+        assert_eq!(summary.total_transactions, 3);
+        assert_eq!(summary.total_supply, 3.0 * blockchain.mining_reward);
+        assert_eq!(summary.difficulty, blockchain.difficulty);
+        assert_eq!(summary.average_block_time, Some(65.0));
+        assert_eq!(summary.mempool_size, 1);
+        
+        // Blocks coming in far slower than targeted make the next one easier
+        blockchain.target_block_time = Some(10);
+        assert_eq!(blockchain.summary().difficulty, blockchain.difficulty - 1);
+        
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["mempool_size"], 1);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+
+use crate::{consensus, Block, BlockPayload, Blockchain, BlockchainError, ConsensusMode, DifficultyMode, HashAlgorithm};
+
+// Everything needed to produce the next block, copied out of the chain so
+// the slow part can run without holding on to it. Hand the block back with
+// `append_validated_block`, which refuses it if the chain has moved on.
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub index: u32,
+    pub previous_hash: String,
+    pub data: BlockPayload,
+    pub metadata: BTreeMap<String, String>,
+    pub difficulty: u32,
+    pub difficulty_mode: DifficultyMode,
+    pub hash_algorithm: HashAlgorithm,
+    pub threads: usize,
+    // The validator to forge it for under proof of stake; mined otherwise
+    pub validator: Option<String>,
+}
+
+impl BlockTemplate {
+    // Mine or forge the block. Under proof of work this can take a while.
+    pub fn mine(self) -> Block {
+        self.mine_until(&AtomicBool::new(false)).expect("mining that is never cancelled always finishes")
+    }
+
+    // Like `mine`, giving up if `stop` is set
+    pub(crate) fn mine_until(self, stop: &AtomicBool) -> Option<Block> {
+        let Some(validator) = self.validator else {
+            return Block::mine_parallel_with(
+                self.index,
+                self.data,
+                self.metadata,
+                self.previous_hash,
+                self.difficulty,
+                self.difficulty_mode,
+                self.hash_algorithm,
+                self.threads,
+                stop
+            ).map(|(block, _)| block);
+        };
+        
+        let mut block = Block::forge(self.index, self.data, self.previous_hash, self.hash_algorithm, validator);
+        if !self.metadata.is_empty() {
+            block.metadata = self.metadata;
+            block.hash = block.compute_hash();
+        }
+        Some(block)
+    }
+}
+
+impl Blockchain {
+    // The block `mine_pending_transactions` would mine next, not yet mined
+    pub fn block_template(&self, miner_address: &str) -> Result<BlockTemplate, BlockchainError> {
+        let (selected, _) = self.select_pending();
+        self.template_for(BlockPayload::Transactions(self.block_transactions(&selected, miner_address)), BTreeMap::new())
+    }
+
+    // A template for the block after our latest, at the difficulty it
+    // has to meet
+    pub(crate) fn template_for(&self, data: BlockPayload, metadata: BTreeMap<String, String>) -> Result<BlockTemplate, BlockchainError> {
+        let latest_block = self.get_latest_block().ok_or(BlockchainError::EmptyChain)?;
+        let validator = match self.consensus {
+            ConsensusMode::ProofOfWork => None,
+            ConsensusMode::ProofOfStake => Some(
+                consensus::select_validator(&self.stakes, &latest_block.hash)
+                    .ok_or(BlockchainError::NoValidator)?
+                    .to_string()
+            ),
+        };
+        
+        Ok(BlockTemplate {
+            index: latest_block.index + 1,
+            previous_hash: latest_block.hash.clone(),
+            data,
+            metadata,
+            difficulty: self.next_difficulty(),
+            difficulty_mode: self.difficulty_mode,
+            hash_algorithm: self.hash_algorithm,
+            threads: self.mining_threads,
+            validator,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, TransactionSigner};
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_block_template() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let mut blockchain = Blockchain::builder().difficulty(1).build();
+        blockchain.mine_pending_transactions(&alice.address()).unwrap();
+        let mut transaction = Transaction::new(alice.address(), "Bob".to_string(), 10.0);
+        transaction.sign(&alice);
+        blockchain.create_transaction(transaction).unwrap();
+        
+        // Mined apart from the chain, then appended like a peer's block
+        let template = blockchain.block_template("Miner1").unwrap();
+        assert_eq!(template.index, 2);
+        assert_eq!(template.data.transactions().len(), 2);
+        let block = template.mine();
+        blockchain.append_validated_block(block.clone()).unwrap();
+        assert_eq!(blockchain.mempool_size(), 0);
+        assert_eq!(blockchain.get_balance_of_address("Bob"), 10.0);
+        assert_eq!(blockchain.get_balance_of_address("Miner1"), blockchain.mining_reward);
+        
+        // Once the chain has moved on, a block from an old template is stale
+        let stale = blockchain.block_template("Miner1").unwrap();
+        blockchain.mine_pending_transactions("Miner2").unwrap();
+        assert!(blockchain.append_validated_block(stale.mine()).is_err());
+        
+        // Under proof of stake it is forged for the selected validator
+        let staked = Blockchain::builder()
+            .consensus(ConsensusMode::ProofOfStake)
+            .stake("Validator".to_string(), 10.0)
+            .build();
+        let template = staked.block_template("Miner1").unwrap();
+        assert_eq!(template.validator.as_deref(), Some("Validator"));
+        assert_eq!(template.mine().validator.as_deref(), Some("Validator"));
+    }
+}
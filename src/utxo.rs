@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{Block, BlockPayload, Blockchain, BlockchainError, Transaction};
+
+// How coins are accounted for
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceModel {
+    // Each address has a running balance that transfers are checked against
+    #[default]
+    Account,
+    // Coins are the outputs of earlier transactions. A transfer names the
+    // outputs it spends, each of which can only be spent once, and whatever
+    // they hold beyond its cost comes back to the sender as change.
+    Utxo,
+}
+
+// The `index`th output of transaction `txid`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutPoint {
+    pub txid: String,
+    pub index: u32,
+}
+
+// Coins held by `address`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Output {
+    pub address: String,
+    pub amount: f64,
+}
+
+// Outputs not yet spent, by where they were created
+pub type UtxoSet = HashMap<OutPoint, Output>;
+
+impl Transaction {
+    // The outputs this transaction creates when its inputs hold `spent`:
+    // the amount to the recipient, then any change back to the sender
+    pub fn outputs(&self, spent: f64) -> Vec<Output> {
+        let mut outputs = vec![Output {
+            address: self.recipient.clone(),
+            amount: self.amount,
+        }];
+        
+        let change = spent - self.cost();
+        if change > 0.0 {
+            outputs.push(Output {
+                address: self.sender.clone(),
+                amount: change,
+            });
+        }
+        outputs
+    }
+}
+
+// What the inputs of a transfer hold, if it may spend them: each must be
+// unspent, belong to the sender and be named once, and together they must
+// cover the cost
+pub fn input_value(utxos: &UtxoSet, transaction: &Transaction) -> Result<f64, BlockchainError> {
+    let invalid = |reason: String| Err(BlockchainError::InvalidTransaction(reason));
+    if transaction.inputs.is_empty() {
+        return invalid("a transfer must spend at least one output".to_string());
+    }
+    
+    let mut spent = 0.0;
+    for (i, input) in transaction.inputs.iter().enumerate() {
+        if transaction.inputs[..i].contains(input) {
+            return invalid(format!("output {}:{} is spent twice", input.txid, input.index));
+        }
+        
+        match utxos.get(input) {
+            Some(output) if output.address == transaction.sender => spent += output.amount,
+            Some(_) => return invalid(format!("output {}:{} belongs to someone else", input.txid, input.index)),
+            None => return invalid(format!("output {}:{} is spent or doesn't exist", input.txid, input.index)),
+        }
+    }
+    
+    if spent < transaction.cost() {
+        return Err(BlockchainError::InsufficientFunds {
+            sender: transaction.sender.clone(),
+            available: spent,
+            requested: transaction.cost(),
+        });
+    }
+    
+    Ok(spent)
+}
+
+// Spend a block's inputs and add its outputs, one transaction at a time,
+// stopping at the first that can't be applied. A snapshot's positive
+// balances become one output each, numbered in address order under the
+// snapshot's hash.
+pub fn apply_block(utxos: &mut UtxoSet, block: &Block) -> Result<(), BlockchainError> {
+    if let BlockPayload::Snapshot(snapshot) = &block.data {
+        let balances = snapshot.balances.iter().filter(|(_, amount)| **amount > 0.0);
+        for (index, (address, amount)) in balances.enumerate() {
+            let outpoint = OutPoint {
+                txid: block.hash.clone(),
+                index: index as u32,
+            };
+            utxos.insert(outpoint, Output { address: address.clone(), amount: *amount });
+        }
+    }
+    
+    for transaction in block.data.transactions() {
+        let spent = if transaction.is_coinbase() {
+            transaction.amount
+        } else {
+            input_value(utxos, transaction)?
+        };
+        
+        // Outputs are named by transaction ID, so a repeat of a transaction
+        // whose outputs are unspent would overwrite them
+        let txid = transaction.id();
+        let outputs = transaction.outputs(spent);
+        let outpoints: Vec<OutPoint> = (0..outputs.len() as u32)
+            .map(|index| OutPoint { txid: txid.clone(), index })
+            .collect();
+        if outpoints.iter().any(|outpoint| utxos.contains_key(outpoint)) {
+            return Err(BlockchainError::InvalidTransaction(format!("transaction {} repeats unspent outputs", txid)));
+        }
+        
+        for input in &transaction.inputs {
+            utxos.remove(input);
+        }
+        utxos.extend(outpoints.into_iter().zip(outputs));
+    }
+    
+    Ok(())
+}
+
+// Whether `apply_block` would accept a block, without copying the set to
+// find out. Only the outputs the block's transactions spend or would
+// create (at most two each: payment and change) are looked up, and the
+// block is applied to a set holding just those.
+pub fn check_block(utxos: &UtxoSet, block: &Block) -> Result<(), BlockchainError> {
+    let mut touched = UtxoSet::new();
+    for transaction in block.data.transactions() {
+        let txid = transaction.id();
+        let created = (0..2).map(|index| OutPoint { txid: txid.clone(), index });
+        for outpoint in transaction.inputs.iter().cloned().chain(created) {
+            if let Some(output) = utxos.get(&outpoint) {
+                touched.insert(outpoint, output.clone());
+            }
+        }
+    }
+    
+    apply_block(&mut touched, block)
+}
+
+// The outputs left unspent by `blocks`, applied in order. Blocks that
+// can't be applied in full count for as much of them as can.
+pub fn utxo_set(blocks: &[Block]) -> UtxoSet {
+    let mut utxos = UtxoSet::new();
+    for block in blocks {
+        let _ = apply_block(&mut utxos, block);
+    }
+    utxos
+}
+
+impl Blockchain {
+    // Confirmed outputs `address` hasn't spent, oldest transaction ID first.
+    // Empty unless the chain uses `BalanceModel::Utxo`.
+    pub fn unspent_outputs(&self, address: &str) -> Vec<(OutPoint, Output)> {
+        let mut outputs: Vec<(OutPoint, Output)> = self.utxos
+            .iter()
+            .filter(|(_, output)| output.address == address)
+            .map(|(outpoint, output)| (outpoint.clone(), output.clone()))
+            .collect();
+        outputs.sort_by(|a, b| a.0.cmp(&b.0));
+        outputs
+    }
+
+    // Enough of `address`'s unspent outputs to pay `cost`, skipping any a
+    // pending transaction already spends and rewards still maturing, for
+    // `Transaction::with_inputs`
+    pub fn select_inputs(&self, address: &str, cost: f64) -> Result<Vec<OutPoint>, BlockchainError> {
+        let mut inputs = Vec::new();
+        let mut total = 0.0;
+        
+        for (outpoint, output) in self.unspent_outputs(address) {
+            if total >= cost {
+                break;
+            }
+            if !self.is_spent_by_pending(&outpoint) && !self.is_immature_reward(&outpoint) {
+                total += output.amount;
+                inputs.push(outpoint);
+            }
+        }
+        
+        if total < cost {
+            return Err(BlockchainError::InsufficientFunds {
+                sender: address.to_string(),
+                available: total,
+                requested: cost,
+            });
+        }
+        Ok(inputs)
+    }
+
+    // Check a transfer only spends confirmed outputs of the sender that no
+    // pending transaction spends already
+    pub(crate) fn validate_inputs(&self, transaction: &Transaction) -> Result<(), BlockchainError> {
+        input_value(&self.utxos, transaction)?;
+        
+        if let Some(input) = transaction.inputs.iter().find(|input| self.is_spent_by_pending(input)) {
+            return Err(BlockchainError::InvalidTransaction(format!(
+                "output {}:{} is already spent by a pending transaction",
+                input.txid, input.index
+            )));
+        }
+        if let Some(input) = transaction.inputs.iter().find(|input| self.is_immature_reward(input)) {
+            return Err(BlockchainError::InvalidTransaction(format!(
+                "output {}:{} is a reward that hasn't matured",
+                input.txid, input.index
+            )));
+        }
+        Ok(())
+    }
+    
+    // Like `validate_inputs`, for a transaction being mined after others
+    // in the same block that spend the outputs in `claimed`
+    pub(crate) fn validate_block_inputs(&self, transaction: &Transaction, claimed: &HashSet<OutPoint>) -> Result<(), BlockchainError> {
+        input_value(&self.utxos, transaction)?;
+        
+        if let Some(input) = transaction.inputs.iter().find(|input| claimed.contains(*input)) {
+            return Err(BlockchainError::InvalidTransaction(format!(
+                "output {}:{} is already spent earlier in the block",
+                input.txid, input.index
+            )));
+        }
+        if let Some(input) = transaction.inputs.iter().find(|input| self.is_immature_reward(input)) {
+            return Err(BlockchainError::InvalidTransaction(format!(
+                "output {}:{} is a reward that hasn't matured",
+                input.txid, input.index
+            )));
+        }
+        Ok(())
+    }
+    
+    fn is_immature_reward(&self, outpoint: &OutPoint) -> bool {
+        self.immature_rewards().any(|reward| reward.id() == outpoint.txid)
+    }
+
+    fn is_spent_by_pending(&self, outpoint: &OutPoint) -> bool {
+        self.pending_transactions.iter().any(|transaction| transaction.inputs.contains(outpoint))
+    }
+
+    // Recompute the unspent outputs from the chain
+    pub(crate) fn rebuild_utxo_set(&mut self) {
+        self.utxos = match self.balance_model {
+            BalanceModel::Utxo => utxo_set(&self.chain),
+            BalanceModel::Account => UtxoSet::new(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionSigner;
+    use ed25519_dalek::SigningKey;
+
+    fn utxo_blockchain() -> Blockchain {
+        Blockchain::builder().difficulty(1).balance_model(BalanceModel::Utxo).build()
+    }
+
+    // A transfer from `key` paid for with whichever of its outputs cover it
+    fn spend(blockchain: &Blockchain, key: &SigningKey, recipient: &str, amount: f64) -> Transaction {
+        let inputs = blockchain.select_inputs(&key.address(), amount).unwrap();
+        let mut transaction = Transaction::new(key.address(), recipient.to_string(), amount).with_inputs(inputs);
+        transaction.sign(key);
+        transaction
+    }
+
+    // Mine transfers into a block without checking them, as a careless
+    // node could
+    fn mine_unchecked(blockchain: &mut Blockchain, mut transactions: Vec<Transaction>) -> Block {
+        let index = blockchain.height() + 1;
+        let mut reward = Transaction::coinbase("Miner2".to_string(), blockchain.block_subsidy(index));
+        reward.nonce = u64::from(index);
+        transactions.push(reward);
+        blockchain
+            .mine_block(BlockPayload::Transactions(transactions), Default::default(), &Default::default())
+            .unwrap()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_spend_with_change() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let mut blockchain = utxo_blockchain();
+        blockchain.mine_pending_transactions(&alice.address()).unwrap();
+        blockchain.mine_pending_transactions(&alice.address()).unwrap();
+        assert_eq!(blockchain.unspent_outputs(&alice.address()).len(), 2);
+        
+        // Two rewards of 100 cover 150, with 50 back as change
+        let payment = spend(&blockchain, &alice, "Bob", 150.0);
+        assert_eq!(payment.inputs.len(), 2);
+        blockchain.create_transaction(payment.clone()).unwrap();
+        blockchain.mine_pending_transactions("Miner1").unwrap();
+        
+        let change = OutPoint { txid: payment.id(), index: 1 };
+        assert_eq!(blockchain.unspent_outputs(&alice.address()), vec![(change, Output { address: alice.address(), amount: 50.0 })]);
+        assert_eq!(blockchain.unspent_outputs("Bob")[0].1.amount, 150.0);
+        assert_eq!(blockchain.balance(&alice.address()), 50.0);
+        assert!(blockchain.is_chain_valid());
+        
+        // Outputs are rebuilt from the chain when it is loaded
+        let filename = "test_utxo_blockchain.json";
+        blockchain.save_to_file(filename).unwrap();
+        let loaded = Blockchain::load_from_file(filename);
+        let _ = std::fs::remove_file(filename);
+        assert_eq!(loaded.unwrap().unspent_outputs(&alice.address()), blockchain.unspent_outputs(&alice.address()));
+    }
+
+    #[test]
+    fn test_double_spend() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let mut blockchain = utxo_blockchain();
+        blockchain.mine_pending_transactions(&alice.address()).unwrap();
+        let inputs = blockchain.select_inputs(&alice.address(), 10.0).unwrap();
+        let mut first = Transaction::new(alice.address(), "Bob".to_string(), 10.0).with_inputs(inputs.clone());
+        first.sign(&alice);
+        let mut second = Transaction::new(alice.address(), "Charlie".to_string(), 20.0).with_inputs(inputs);
+        second.sign(&alice);
+        
+        // The same output can't be spent twice, pending or confirmed
+        blockchain.create_transaction(first).unwrap();
+        assert!(matches!(blockchain.create_transaction(second.clone()), Err(BlockchainError::InvalidTransaction(_))));
+        assert!(blockchain.select_inputs(&alice.address(), 10.0).is_err());
+        blockchain.mine_pending_transactions("Miner1").unwrap();
+        assert!(matches!(blockchain.create_transaction(second.clone()), Err(BlockchainError::InvalidTransaction(_))));
+        
+        // Nor can a block another node mined without checking
+        let mut careless = blockchain.clone();
+        let block = mine_unchecked(&mut careless, vec![second]);
+        assert!(blockchain.validate_external_block(&block).is_err());
+        assert_eq!(careless.first_invalid_block(), Some(block.index));
+        
+        // Or spent twice within one block
+        let mut twice = blockchain.clone();
+        let inputs = twice.select_inputs(&alice.address(), 10.0).unwrap();
+        let transfers = ["Bob", "Charlie"].map(|recipient| {
+            let mut transaction = Transaction::new(alice.address(), recipient.to_string(), 10.0).with_inputs(inputs.clone());
+            transaction.sign(&alice);
+            transaction
+        });
+        let block = mine_unchecked(&mut twice, transfers.to_vec());
+        assert!(check_block(&blockchain.utxos, &block).is_err());
+        assert!(blockchain.validate_external_block(&block).is_err());
+        
+        // Transfers must say what they spend
+        let mut unfunded = Transaction::new(alice.address(), "Bob".to_string(), 1.0);
+        unfunded.sign(&alice);
+        assert!(blockchain.create_transaction(unfunded).is_err());
+    }
+
+    #[test]
+    fn test_immature_outputs() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let mut blockchain = Blockchain::builder()
+            .difficulty(1)
+            .balance_model(BalanceModel::Utxo)
+            .coinbase_maturity(1)
+            .build();
+        blockchain.mine_pending_transactions(&alice.address()).unwrap();
+        
+        // The reward's output exists but can't be chosen or spent yet
+        let (reward, _) = blockchain.unspent_outputs(&alice.address())[0].clone();
+        assert!(blockchain.select_inputs(&alice.address(), 10.0).is_err());
+        let mut early = Transaction::new(alice.address(), "Bob".to_string(), 10.0).with_inputs(vec![reward.clone()]);
+        early.sign(&alice);
+        assert!(matches!(blockchain.create_transaction(early), Err(BlockchainError::InvalidTransaction(_))));
+        
+        blockchain.add_block("Block 2".to_string()).unwrap();
+        assert_eq!(blockchain.select_inputs(&alice.address(), 10.0).unwrap(), vec![reward]);
+    }
+}
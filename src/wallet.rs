@@ -0,0 +1,133 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use ed25519_dalek::SigningKey;
+
+use crate::{BlockchainError, Transaction, TransactionSigner};
+
+// An Ed25519 key pair and the address it controls. Builds and signs
+// transactions so callers never handle raw key bytes.
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    signing_key: SigningKey,
+}
+
+impl Wallet {
+    // A wallet with a freshly generated key
+    pub fn generate() -> Result<Wallet, BlockchainError> {
+        let mut secret = [0u8; 32];
+        getrandom::fill(&mut secret)
+            .map_err(|e| BlockchainError::InvalidKey(format!("could not generate a key: {}", e)))?;
+        
+        Ok(Wallet::from_signing_key(SigningKey::from_bytes(&secret)))
+    }
+
+    pub fn from_signing_key(signing_key: SigningKey) -> Wallet {
+        Wallet { signing_key }
+    }
+
+    // The hex-encoded public key that funds are sent to
+    pub fn address(&self) -> String {
+        self.signing_key.address()
+    }
+
+    // Build a transaction from this wallet and sign it
+    pub fn create_signed_transaction(&self, recipient: &str, amount: f64) -> Transaction {
+        let mut transaction = Transaction::new(self.address(), recipient.to_string(), amount);
+        transaction.sign(&self.signing_key);
+        transaction
+    }
+
+    // Write the secret key to `path` as hex. Anyone who can read the file
+    // can spend from the wallet, so on Unix it is readable by its owner
+    // only before the key goes in, whether it is new or overwritten.
+    pub fn save(&self, path: &str) -> Result<(), BlockchainError> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        
+        // `mode` only applies to files it creates
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        
+        file.write_all(hex::encode(self.signing_key.to_bytes()).as_bytes())?;
+        Ok(())
+    }
+
+    // Read a wallet written by `save`
+    pub fn load(path: &str) -> Result<Wallet, BlockchainError> {
+        let contents = fs::read_to_string(path)?;
+        let secret: [u8; 32] = hex::decode(contents.trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| BlockchainError::InvalidKey(format!("{} does not hold a hex-encoded Ed25519 key", path)))?;
+        
+        Ok(Wallet::from_signing_key(SigningKey::from_bytes(&secret)))
+    }
+}
+
+impl TransactionSigner for Wallet {
+    fn address(&self) -> String {
+        self.signing_key.address()
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Vec<u8> {
+        self.signing_key.sign_message(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_wallets_differ() {
+        let first = Wallet::generate().unwrap();
+        let second = Wallet::generate().unwrap();
+        
+        assert_ne!(first.address(), second.address());
+        assert_eq!(first.address().len(), 64);
+    }
+
+    #[test]
+    fn test_create_signed_transaction() {
+        let wallet = Wallet::generate().unwrap();
+        let transaction = wallet.create_signed_transaction("Bob", 10.0);
+        
+        assert_eq!(transaction.sender, wallet.address());
+        assert_eq!(transaction.recipient, "Bob");
+        assert!(transaction.is_valid());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = "test_wallet.key";
+        let wallet = Wallet::generate().unwrap();
+        wallet.save(path).unwrap();
+        
+        let loaded = Wallet::load(path).unwrap();
+        assert_eq!(loaded.address(), wallet.address());
+        
+        fs::write(path, "not a key").unwrap();
+        assert!(matches!(Wallet::load(path), Err(BlockchainError::InvalidKey(_))));
+        
+        // Saving over a file others could read locks it down first
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+            wallet.save(path).unwrap();
+            assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,72 @@
+#![cfg(all(feature = "server", feature = "networking"))]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rapid_blockchain::prelude::*;
+use rapid_blockchain::server;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+type Shared = Arc<Mutex<Blockchain>>;
+
+// Serve `blockchain` on an ephemeral port and return its URL
+async fn start(blockchain: Shared) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(server::serve(listener, blockchain));
+    url
+}
+
+async fn mine(url: &str) {
+    let address = url.trim_start_matches("http://");
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!("POST /mine?miner=Miner1 HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", address);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+// Wait up to five seconds for `blockchain` to reach `len` blocks
+async fn wait_for_len(blockchain: &Shared, len: usize) {
+    for _ in 0..100 {
+        if blockchain.lock().unwrap().len() == len {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("chain has {} blocks, expected {}", blockchain.lock().unwrap().len(), len);
+}
+
+#[tokio::test]
+async fn test_mined_blocks_reach_peers() {
+    let a: Shared = Arc::new(Mutex::new(Blockchain::new(1, 100.0)));
+    let b: Shared = Arc::new(Mutex::new(a.lock().unwrap().clone()));
+    let (a_url, b_url) = (start(a.clone()).await, start(b.clone()).await);
+    a.lock().unwrap().register_node(b_url.clone());
+    b.lock().unwrap().register_node(a_url.clone());
+    
+    // Mining on A grows B, and the other way round
+    mine(&a_url).await;
+    wait_for_len(&b, 2).await;
+    mine(&b_url).await;
+    wait_for_len(&a, 3).await;
+    assert_eq!(a.lock().unwrap().chain[2].hash, b.lock().unwrap().chain[2].hash);
+    
+    // With A no longer announcing to B, B misses two blocks. Hearing of the
+    // latest sends it back to A for the rest of the chain.
+    a.lock().unwrap().set_node_active(&b_url, false);
+    mine(&a_url).await;
+    mine(&a_url).await;
+    let latest = a.lock().unwrap().get_latest_block().unwrap().clone();
+    let mut copy = a.lock().unwrap().clone();
+    copy.register_node(b_url.clone());
+    assert_eq!(copy.broadcast_block(&latest).await, 1);
+    wait_for_len(&b, 5).await;
+    assert!(b.lock().unwrap().is_chain_valid());
+    
+    // A block B already has is ignored
+    assert_eq!(copy.broadcast_block(&latest).await, 1);
+    assert_eq!(b.lock().unwrap().len(), 5);
+}
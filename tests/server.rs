@@ -0,0 +1,97 @@
+#![cfg(feature = "server")]
+
+use std::sync::{Arc, Mutex};
+
+use ed25519_dalek::SigningKey;
+use rapid_blockchain::prelude::*;
+use rapid_blockchain::{server, TransactionSigner};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// Serve `blockchain` on an ephemeral port and return the address
+async fn start(blockchain: Arc<Mutex<Blockchain>>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(server::serve(listener, blockchain));
+    address
+}
+
+// Minimal HTTP/1.1 client: send one request and return the status code
+// and the JSON body
+async fn request(address: &str, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method, path, address, body.len(), body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.to_lowercase().contains("content-type: application/json"), "{}", head);
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[tokio::test]
+async fn test_get_chain() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(2, 100.0)));
+    let address = start(blockchain.clone()).await;
+    
+    let (status, chain) = request(&address, "GET", "/chain", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(chain["chain"].as_array().unwrap().len(), 1);
+    assert_eq!(chain["chain"][0]["hash"], blockchain.lock().unwrap().chain[0].hash);
+}
+
+#[tokio::test]
+async fn test_transaction_round_trip() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(2, 100.0)));
+    let address = start(blockchain.clone()).await;
+    
+    let alice = SigningKey::from_bytes(&[1; 32]);
+    let alice_address = alice.address();
+
+    // Give Alice something to spend
+    let (status, mined) = request(&address, "POST", &format!("/mine?miner={}", alice_address), "").await;
+    assert_eq!(status, 200);
+    assert_eq!(mined["block"]["index"], 1);
+    
+    let mut transaction = Transaction::new(alice_address.clone(), "Bob".to_string(), 30.0);
+    transaction.sign(&alice);
+    let body = serde_json::to_string(&transaction).unwrap();
+    let (status, submitted) = request(&address, "POST", "/transactions", &body).await;
+    assert_eq!(status, 201);
+    assert_eq!(submitted["message"], "Transaction added to pending transactions");
+    
+    // The same transaction again is a conflict
+    let (status, duplicate) = request(&address, "POST", "/transactions", &body).await;
+    assert_eq!(status, 409);
+    assert!(duplicate["error"].as_str().unwrap().contains("already pending"));
+    
+    request(&address, "POST", "/mine/Miner1", "").await;
+    let (status, balance) = request(&address, "GET", "/balance/Bob", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(balance["balance"], 30.0);
+
+    let (_, chain) = request(&address, "GET", "/chain", "").await;
+    assert_eq!(chain["chain"].as_array().unwrap().len(), 3);
+    assert!(blockchain.lock().unwrap().is_chain_valid());
+}
+
+#[tokio::test]
+async fn test_rejected_transaction() {
+    let blockchain = Arc::new(Mutex::new(Blockchain::new(2, 100.0)));
+    let address = start(blockchain.clone()).await;
+    
+    // Alice has nothing to spend
+    let alice = SigningKey::from_bytes(&[1; 32]);
+    let mut transaction = Transaction::new(alice.address(), "Bob".to_string(), 30.0);
+    transaction.sign(&alice);
+    let (status, rejected) = request(&address, "POST", "/transactions", &serde_json::to_string(&transaction).unwrap()).await;
+    assert_eq!(status, 422);
+    assert!(rejected["error"].as_str().unwrap().contains("Insufficient funds"));
+    assert_eq!(blockchain.lock().unwrap().mempool_size(), 0);
+}